@@ -9,10 +9,13 @@
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 // Just need serde's Error in scope to get its trait methods
 use serde::de::Error as _;
-use snafu::ensure;
+use lazy_static::lazy_static;
+use regex::Regex;
+use snafu::{ensure, OptionExt, ResultExt};
 use std::borrow::Borrow;
 use std::convert::TryFrom;
 use std::fmt;
+use std::net::{Ipv4Addr, Ipv6Addr};
 use std::ops::Deref;
 
 pub mod error {
@@ -36,12 +39,43 @@ pub mod error {
         #[snafu(display("Given invalid URL '{}'", input))]
         InvalidUrl { input: String },
 
+        #[snafu(display(
+            "Given URL '{}' has scheme '{}', expected one of: {}",
+            input,
+            scheme,
+            expected
+        ))]
+        InvalidUrlScheme {
+            input: String,
+            scheme: String,
+            expected: String,
+        },
+
         // Some regexes are too big to usefully display in an error.
         #[snafu(display("{} given invalid input: {}", thing, input))]
         BigPattern { thing: String, input: String },
 
         #[snafu(display("Given invalid cluster name '{}': {}", name, msg))]
         InvalidClusterName { name: String, msg: String },
+
+        #[snafu(display("Given invalid hostname '{}': {}", input, msg))]
+        InvalidHostname { input: String, msg: String },
+
+        #[snafu(display(
+            "Host '{}' contains forbidden character '{}'",
+            input,
+            character
+        ))]
+        ForbiddenHostCharacter { input: String, character: String },
+
+        #[snafu(display("Host '{}' is not valid IDNA: {}", input, source))]
+        InvalidIdna { input: String, source: idna::Errors },
+
+        #[snafu(display("Host '{}' looks like an IP literal but isn't a valid one", input))]
+        InvalidIpLiteral { input: String },
+
+        #[snafu(display("Given invalid URI reference '{}': {}", input, msg))]
+        InvalidUriReference { input: String, msg: String },
     }
 }
 
@@ -251,34 +285,277 @@ mod test_valid_identifier {
 
 // =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
 
-/// Url represents a string that contains a valid URL, according to url::Url, though it also
-/// allows URLs without a scheme (e.g. without "http://") because it's common.  It stores the
-/// original string and makes it accessible through standard traits. Its purpose is to validate
-/// input for any field containing a network address.
+/// WHATWG "forbidden host code points": https://url.spec.whatwg.org/#forbidden-host-code-point
+/// A host containing any of these is rejected outright, before we try IDNA or IPv4 parsing;
+/// bracketed IPv6 literals are handled before this check.
+fn check_forbidden_host_chars(input: &str) -> Result<(), error::Error> {
+    for c in input.chars() {
+        let forbidden = matches!(c,
+            '\u{0000}'..='\u{001F}'
+                | '\u{007F}'
+                | ' '
+                | '#'
+                | '%'
+                | '/'
+                | ':'
+                | '<'
+                | '>'
+                | '?'
+                | '@'
+                | '['
+                | '\\'
+                | ']'
+                | '^'
+                | '|'
+        );
+        ensure!(
+            !forbidden,
+            error::ForbiddenHostCharacter {
+                input,
+                character: c.to_string(),
+            }
+        );
+    }
+    Ok(())
+}
+
+/// Checks that a (already IDNA-normalized, ASCII) hostname label is a valid RFC 1123 label: 1-63
+/// ASCII alphanumerics or hyphens, not starting or ending with a hyphen.
+fn check_hostname_label(input: &str, label: &str) -> Result<(), error::Error> {
+    ensure!(
+        !label.is_empty(),
+        error::InvalidHostname {
+            input,
+            msg: "hostname labels cannot be empty",
+        }
+    );
+    ensure!(
+        label.len() <= 63,
+        error::InvalidHostname {
+            input,
+            msg: format!("label '{}' is longer than 63 characters", label),
+        }
+    );
+    ensure!(
+        label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-'),
+        error::InvalidHostname {
+            input,
+            msg: format!(
+                "label '{}' may only contain ASCII alphanumerics and hyphens",
+                label
+            ),
+        }
+    );
+    ensure!(
+        !label.starts_with('-') && !label.ends_with('-'),
+        error::InvalidHostname {
+            input,
+            msg: format!("label '{}' cannot start or end with a hyphen", label),
+        }
+    );
+    Ok(())
+}
+
+/// Hostname can only be created by deserializing from a string that is a valid DNS hostname as
+/// described in RFC 1123: labels of 1-63 ASCII alphanumerics or hyphens, separated by dots, where
+/// no label starts or ends with a hyphen, and the full name is no longer than 253 characters.  A
+/// single trailing dot, marking the fully-qualified form, is allowed and stripped before label
+/// checks.
+///
+/// Before any of that, a bracketed IPv6 literal like `[::1]` is accepted if its contents parse as
+/// an IPv6 address; any other input is checked against the WHATWG "forbidden host code points".
+/// A name whose final label is entirely numeric must then be a valid IPv4 address.  IP literals
+/// are stored as given.  Any other name is converted to its ASCII/punycode form via IDNA, which
+/// also lowercases ASCII letters, and that normalized form (plus any trailing dot) is what gets
+/// stored; the 253-character limit applies to both the input and the normalized form.  It
+/// makes its contents accessible through standard traits. Its purpose is to validate input for
+/// fields that hold a plain hostname rather than a full URL, like NTP servers, registry mirrors,
+/// and node names.
 #[derive(Debug, Clone, Eq, PartialEq, Hash)]
-pub struct Url {
+pub struct Hostname {
     inner: String,
 }
 
-impl TryFrom<&str> for Url {
+impl TryFrom<&str> for Hostname {
     type Error = error::Error;
 
     fn try_from(input: &str) -> Result<Self, Self::Error> {
-        if let Ok(_) = input.parse::<url::Url>() {
-            return Ok(Url {
+        ensure!(
+            !input.is_empty(),
+            error::InvalidHostname {
+                input,
+                msg: "hostname cannot be empty",
+            }
+        );
+
+        // Bracketed IPv6 literal, e.g. "[::1]"; don't run IDNA on the contents.
+        if let Some(inner) = input.strip_prefix('[') {
+            let inner = inner
+                .strip_suffix(']')
+                .context(error::InvalidIpLiteral { input })?;
+            inner
+                .parse::<Ipv6Addr>()
+                .ok()
+                .context(error::InvalidIpLiteral { input })?;
+            return Ok(Hostname {
                 inner: input.to_string(),
             });
-        } else {
-            // It's very common to specify URLs without a scheme, so we add one and see if that
-            // fixes parsing.
-            let prefixed = format!("http://{}", input);
-            if let Ok(_) = prefixed.parse::<url::Url>() {
-                return Ok(Url {
+        }
+
+        check_forbidden_host_chars(input)?;
+
+        ensure!(
+            input.len() <= 253,
+            error::InvalidHostname {
+                input,
+                msg: "hostname cannot be longer than 253 characters",
+            }
+        );
+
+        // A single trailing dot marks the fully-qualified form; strip it before checking labels.
+        let fqdn = input.ends_with('.');
+        let unqualified = input.strip_suffix('.').unwrap_or(input);
+
+        // A name whose final label is entirely numeric (including a single all-numeric label)
+        // must be an IPv4 address, not a domain name - this mirrors the WHATWG host-parsing
+        // algorithm.
+        if let Some(last_label) = unqualified.rsplit('.').next() {
+            if !last_label.is_empty() && last_label.chars().all(|c| c.is_ascii_digit()) {
+                unqualified
+                    .parse::<Ipv4Addr>()
+                    .ok()
+                    .context(error::InvalidIpLiteral { input })?;
+                return Ok(Hostname {
                     inner: input.to_string(),
                 });
             }
         }
-        error::InvalidUrl { input }.fail()
+
+        // Normalize any internationalized labels to ASCII/punycode; for names that are already
+        // plain ASCII this only lowercases them.
+        let ascii = idna::domain_to_ascii(unqualified).context(error::InvalidIdna { input })?;
+
+        for label in ascii.split('.') {
+            check_hostname_label(input, label)?;
+        }
+
+        // Punycode can be longer than the input, so check the normalized form's length too.
+        ensure!(
+            ascii.len() <= 253,
+            error::InvalidHostname {
+                input,
+                msg: "hostname cannot be longer than 253 characters",
+            }
+        );
+
+        Ok(Hostname {
+            inner: if fqdn { format!("{}.", ascii) } else { ascii },
+        })
+    }
+}
+
+string_impls_for!(Hostname, "Hostname");
+
+#[cfg(test)]
+mod test_hostname {
+    use super::Hostname;
+    use std::convert::TryFrom;
+
+    #[test]
+    fn good_hostnames() {
+        for ok in &[
+            "example.com",
+            "a--b.co",
+            "localhost",
+            "example.com.",
+            "127.0.0.1",
+            "[::1]",
+            "bücher.example",
+        ] {
+            Hostname::try_from(*ok).unwrap();
+        }
+    }
+
+    #[test]
+    fn idna_normalization() {
+        let host = Hostname::try_from("bücher.example").unwrap();
+        assert_eq!(host.as_ref(), "xn--bcher-kva.example");
+    }
+
+    #[test]
+    fn bad_hostnames() {
+        let too_long = std::iter::repeat("a").take(300).collect::<String>();
+        for err in &[
+            "",
+            "-a.com",
+            "a-.com",
+            "host..name",
+            too_long.as_str(),
+            "999.999.999.999",
+            "[::zzzz]",
+            "exa mple.com",
+            "exa#mple.com",
+        ] {
+            Hostname::try_from(*err).unwrap_err();
+        }
+    }
+}
+
+// =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
+
+/// Url represents a string that contains a valid URL, according to url::Url, though it also
+/// allows URLs without a scheme (e.g. without "http://") because it's common.  It stores the
+/// original string, plus the parsed `url::Url`, and makes both accessible through standard
+/// traits and a handful of accessors. Its purpose is to validate input for any field containing
+/// a network address. `url::Url` itself performs IDNA normalization and WHATWG forbidden host
+/// code point / IP literal checks on the host it parses, so `Url` gets those for free; see
+/// `Hostname` for the equivalent hand-rolled checks used when there's no surrounding URL syntax.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct Url {
+    inner: String,
+    parsed: url::Url,
+}
+
+/// Tries to parse `input` as a URL, adding a "http://" prefix and retrying if the first attempt
+/// fails, since it's very common to specify URLs without a scheme.  Returns the parsed
+/// `url::Url` on success.
+fn parse_url(input: &str) -> Result<url::Url, error::Error> {
+    if let Ok(parsed) = input.parse::<url::Url>() {
+        return Ok(parsed);
+    }
+    let prefixed = format!("http://{}", input);
+    if let Ok(parsed) = prefixed.parse::<url::Url>() {
+        return Ok(parsed);
+    }
+    error::InvalidUrl { input }.fail()
+}
+
+impl TryFrom<&str> for Url {
+    type Error = error::Error;
+
+    fn try_from(input: &str) -> Result<Self, Self::Error> {
+        let parsed = parse_url(input)?;
+        Ok(Url {
+            inner: input.to_string(),
+            parsed,
+        })
+    }
+}
+
+impl Url {
+    /// Returns the scheme of the URL, e.g. "https" for "https://example.com".
+    pub fn scheme(&self) -> &str {
+        self.parsed.scheme()
+    }
+
+    /// Returns the host of the URL, if any, e.g. "example.com" for "https://example.com/path".
+    pub fn host_str(&self) -> Option<&str> {
+        self.parsed.host_str()
+    }
+
+    /// Returns the port of the URL, if explicitly given.
+    pub fn port(&self) -> Option<u16> {
+        self.parsed.port()
     }
 }
 
@@ -319,3 +596,274 @@ mod test_url {
         }
     }
 }
+
+// =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
+
+/// Helper macro for generating a scheme-restricted URL type, e.g. a type that only accepts
+/// "https" URLs.  Pass the name of the type and a list of the schemes it should allow; anything
+/// that doesn't parse as a URL, or parses with a different scheme, is rejected.  Like `Url`, it
+/// retains the parsed `url::Url` so callers can reach `scheme()`, `host_str()`, and `port()`
+/// without re-parsing.
+macro_rules! url_type_with_schemes {
+    ($for:ident, $schemes:expr) => {
+        #[derive(Debug, Clone, Eq, PartialEq, Hash)]
+        pub struct $for {
+            inner: String,
+            parsed: url::Url,
+        }
+
+        impl TryFrom<&str> for $for {
+            type Error = error::Error;
+
+            fn try_from(input: &str) -> Result<Self, Self::Error> {
+                let parsed = parse_url(input)?;
+                let schemes: &[&str] = $schemes;
+                ensure!(
+                    schemes.contains(&parsed.scheme()),
+                    error::InvalidUrlScheme {
+                        input,
+                        scheme: parsed.scheme(),
+                        expected: schemes.join(", "),
+                    }
+                );
+                Ok(Self {
+                    inner: input.to_string(),
+                    parsed,
+                })
+            }
+        }
+
+        impl $for {
+            /// Returns the scheme of the URL, which is guaranteed to be one of the allowed
+            /// schemes for this type.
+            pub fn scheme(&self) -> &str {
+                self.parsed.scheme()
+            }
+
+            /// Returns the host of the URL, if any.
+            pub fn host_str(&self) -> Option<&str> {
+                self.parsed.host_str()
+            }
+
+            /// Returns the port of the URL, if explicitly given.
+            pub fn port(&self) -> Option<u16> {
+                self.parsed.port()
+            }
+        }
+
+        string_impls_for!($for, stringify!($for));
+    };
+}
+
+/// HttpsUrl only accepts URLs with an "https" scheme, for fields like update repository or
+/// metrics endpoints where we never want to allow plaintext or non-network schemes.
+url_type_with_schemes!(HttpsUrl, &["https"]);
+
+#[cfg(test)]
+mod test_https_url {
+    use super::HttpsUrl;
+    use std::convert::TryFrom;
+
+    #[test]
+    fn good_https_urls() {
+        for ok in &["https://example.com", "https://example.com/path"] {
+            HttpsUrl::try_from(*ok).unwrap();
+        }
+    }
+
+    #[test]
+    fn bad_https_urls() {
+        for err in &[
+            "http://example.com",
+            "file:///etc/passwd",
+            "javascript:alert(1)",
+            "ntp://example.com",
+            "how are you",
+        ] {
+            HttpsUrl::try_from(*err).unwrap_err();
+        }
+    }
+}
+
+// =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
+
+/// The printable ASCII members of the WHATWG percent-encode sets for the path, query, and
+/// fragment components of a URI reference; C0 controls and non-ASCII characters are not checked.
+/// https://url.spec.whatwg.org/#percent-encoded-bytes
+const URI_PATH_FORBIDDEN: &[char] = &[' ', '"', '<', '>', '`', '#', '?', '{', '}'];
+const URI_QUERY_FORBIDDEN: &[char] = &[' ', '"', '#', '<', '>'];
+const URI_FRAGMENT_FORBIDDEN: &[char] = &[' ', '"', '<', '>', '`'];
+
+/// Checks `component` for unescaped characters in `forbidden`, and that every `%` in it begins a
+/// valid two-hex-digit percent-encoding.
+fn check_uri_component(
+    input: &str,
+    component: &str,
+    forbidden: &[char],
+) -> Result<(), error::Error> {
+    let bytes = component.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let valid_hex_digit = |b: Option<&u8>| b.map_or(false, |b| (*b as char).is_ascii_hexdigit());
+            ensure!(
+                valid_hex_digit(bytes.get(i + 1)) && valid_hex_digit(bytes.get(i + 2)),
+                error::InvalidUriReference {
+                    input,
+                    msg: format!("'{}' has a dangling or invalid percent-encoding", component),
+                }
+            );
+            i += 3;
+        } else {
+            i += 1;
+        }
+    }
+
+    for c in component.chars() {
+        ensure!(
+            !forbidden.contains(&c),
+            error::InvalidUriReference {
+                input,
+                msg: format!("'{}' contains forbidden character '{}'", component, c),
+            }
+        );
+    }
+    Ok(())
+}
+
+/// UriReference represents a relative-reference as described in RFC 3986: a path, optionally
+/// followed by a `?query` and/or a `#fragment`.  This is the right type for config fields that
+/// hold a path-and-query reference rather than an absolute URL, like a registry path or a
+/// webhook route; `Url` would read the first path segment of such a reference as a host, via its
+/// "http://" retry.  Only the characters of each component are checked: input with a scheme or
+/// an authority is not rejected.  It stores the original string and makes it accessible through
+/// standard traits. Its
+/// purpose is to validate input for any field containing such a reference.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct UriReference {
+    inner: String,
+}
+
+impl TryFrom<&str> for UriReference {
+    type Error = error::Error;
+
+    fn try_from(input: &str) -> Result<Self, Self::Error> {
+        let (path_and_query, fragment) = match input.split_once('#') {
+            Some((pq, f)) => (pq, Some(f)),
+            None => (input, None),
+        };
+        let (path, query) = match path_and_query.split_once('?') {
+            Some((p, q)) => (p, Some(q)),
+            None => (path_and_query, None),
+        };
+
+        check_uri_component(input, path, URI_PATH_FORBIDDEN)?;
+        if let Some(query) = query {
+            check_uri_component(input, query, URI_QUERY_FORBIDDEN)?;
+        }
+        if let Some(fragment) = fragment {
+            check_uri_component(input, fragment, URI_FRAGMENT_FORBIDDEN)?;
+        }
+
+        Ok(UriReference {
+            inner: input.to_string(),
+        })
+    }
+}
+
+string_impls_for!(UriReference, "UriReference");
+
+#[cfg(test)]
+mod test_uri_reference {
+    use super::UriReference;
+    use std::convert::TryFrom;
+
+    #[test]
+    fn good_uri_references() {
+        for ok in &[
+            "/v2/repo/manifests",
+            "/v2/repo/manifests?tag=x",
+            "/v2/repo/manifests?tag=x#frag",
+            "/v2/repo%2Fmanifests",
+        ] {
+            UriReference::try_from(*ok).unwrap();
+        }
+    }
+
+    #[test]
+    fn bad_uri_references() {
+        for err in &["/v2/repo manifests", "/v2/repo%2", "/v2/repo%2zmanifests"] {
+            UriReference::try_from(*err).unwrap_err();
+        }
+    }
+}
+
+// =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
+
+/// Helper macro for defining a modeled type that's valid if it matches a given regular
+/// expression.  Pass the type name, a display name for error messages (used in the `BigPattern`
+/// error), and a compiled `Regex`.  The input is accepted if `is_match` finds the regex anywhere
+/// in it, so only a regex anchored with `^` and `$` checks the whole input.  This lets model authors
+/// declare regex-validated string types, like Kubernetes label values or version strings, in a
+/// few lines instead of hand-writing the struct.
+macro_rules! pattern_type {
+    ($for:ident, $display:expr, $pattern:expr) => {
+        #[derive(Debug, Clone, Eq, PartialEq, Hash)]
+        pub struct $for {
+            inner: String,
+        }
+
+        impl TryFrom<&str> for $for {
+            type Error = error::Error;
+
+            fn try_from(input: &str) -> Result<Self, Self::Error> {
+                ensure!(
+                    $pattern.is_match(input),
+                    error::BigPattern {
+                        thing: $display,
+                        input,
+                    }
+                );
+                Ok(Self {
+                    inner: input.to_string(),
+                })
+            }
+        }
+
+        string_impls_for!($for, $display);
+    };
+}
+
+lazy_static! {
+    /// Matches a valid Kubernetes label value: optionally empty, or 1-63 characters starting and
+    /// ending with an alphanumeric, with alphanumerics, '-', '_', and '.' in between.
+    /// https://kubernetes.io/docs/concepts/overview/working-with-objects/labels/#syntax-and-character-set
+    static ref KUBERNETES_LABEL_VALUE: Regex =
+        Regex::new(r"^(([A-Za-z0-9][-A-Za-z0-9_.]{0,61})?[A-Za-z0-9])?$").unwrap();
+}
+
+pattern_type!(
+    KubernetesLabelValue,
+    "Kubernetes label value",
+    KUBERNETES_LABEL_VALUE
+);
+
+#[cfg(test)]
+mod test_kubernetes_label_value {
+    use super::KubernetesLabelValue;
+    use std::convert::TryFrom;
+
+    #[test]
+    fn good_label_values() {
+        for ok in &["", "MyValue", "my.value-1_2", "a"] {
+            KubernetesLabelValue::try_from(*ok).unwrap();
+        }
+    }
+
+    #[test]
+    fn bad_label_values() {
+        for err in &["my value", "-leading-hyphen", "trailing-hyphen-"] {
+            KubernetesLabelValue::try_from(*err).unwrap_err();
+        }
+    }
+}